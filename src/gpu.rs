@@ -0,0 +1,251 @@
+//! Optional GPU compute backend for `World::update`.
+//!
+//! The CPU path in `main.rs` walks every cell and does a rule-table lookup
+//! per cell; this module does the same work as a single compute dispatch.
+//! Two `cells` storage buffers are ping-ponged each generation so the
+//! shader can read the previous generation while writing the next one
+//! without a read/write hazard, and the rule table is uploaded once (and
+//! again whenever it changes) as a third read-only storage buffer.
+//!
+//! Only a `Rules::Dense` table can be uploaded as a single buffer, so this
+//! backend is always constructed from a dense slice; `World::update`
+//! falls back to the CPU path for `Rules::Sparse`.
+
+use crate::cell::Cell;
+use crate::{HEIGHT_USIZE, WIDTH_USIZE};
+use pixels::wgpu::{self, util::DeviceExt};
+
+const SHADER_SRC: &str = include_str!("shaders/update.wgsl");
+const WORKGROUP_SIZE: u32 = 64;
+
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct Dims {
+    width: u32,
+    height: u32,
+    species_count: u32,
+    _padding: u32,
+}
+
+/// GPU resources for running `World::update` as a compute shader.
+///
+/// `front` tracks which of the two `cells` buffers currently holds the
+/// live generation; `step` swaps it after every dispatch.
+pub struct ComputeBackend {
+    pipeline: wgpu::ComputePipeline,
+    cells: [wgpu::Buffer; 2],
+    dims: wgpu::Buffer,
+    rules: wgpu::Buffer,
+    bind_groups: [wgpu::BindGroup; 2],
+    front: usize,
+}
+
+impl ComputeBackend {
+    pub fn new(device: &wgpu::Device, dense_rules: &[Cell], species_count: u8) -> Self {
+        let cell_count = (WIDTH_USIZE * HEIGHT_USIZE) as u64;
+
+        let cells = [
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("cellulars cells buffer (a)"),
+                size: cell_count * std::mem::size_of::<u32>() as u64,
+                usage: wgpu::BufferUsages::STORAGE
+                    | wgpu::BufferUsages::COPY_SRC
+                    | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }),
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("cellulars cells buffer (b)"),
+                size: cell_count * std::mem::size_of::<u32>() as u64,
+                usage: wgpu::BufferUsages::STORAGE
+                    | wgpu::BufferUsages::COPY_SRC
+                    | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }),
+        ];
+
+        let dims = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("cellulars dims uniform"),
+            contents: bytemuck::bytes_of(&Dims {
+                width: WIDTH_USIZE as u32,
+                height: HEIGHT_USIZE as u32,
+                species_count: species_count as u32,
+                _padding: 0,
+            }),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let rules_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("cellulars rule table buffer"),
+            contents: bytemuck::cast_slice(&flatten_rule_table(dense_rules)),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("cellulars update shader"),
+            source: wgpu::ShaderSource::Wgsl(SHADER_SRC.into()),
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("cellulars update bind group layout"),
+            entries: &[
+                storage_entry(0, true),
+                storage_entry(1, false),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                storage_entry(3, true),
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("cellulars update pipeline layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("cellulars update pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: "main",
+        });
+
+        // `bind_groups[0]` reads buffer 0 and writes buffer 1; `bind_groups[1]`
+        // is the same layout with the two cell buffers swapped.
+        let bind_groups = [
+            Self::make_bind_group(device, &bind_group_layout, &cells[0], &cells[1], &dims, &rules_buffer),
+            Self::make_bind_group(device, &bind_group_layout, &cells[1], &cells[0], &dims, &rules_buffer),
+        ];
+
+        Self {
+            pipeline,
+            cells,
+            dims,
+            rules: rules_buffer,
+            bind_groups,
+            front: 0,
+        }
+    }
+
+    fn make_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        src: &wgpu::Buffer,
+        dst: &wgpu::Buffer,
+        dims: &wgpu::Buffer,
+        rules: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("cellulars update bind group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: src.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: dst.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: dims.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: rules.as_entire_binding(),
+                },
+            ],
+        })
+    }
+
+    /// Re-uploads the rule table, e.g. after a new randomized ruleset.
+    ///
+    /// The table must be the same size this backend was created with
+    /// (same species count); `World::set_species_count` drops the backend
+    /// entirely rather than trying to resize it in place.
+    pub fn set_rules(&mut self, queue: &wgpu::Queue, dense_rules: &[Cell]) {
+        queue.write_buffer(&self.rules, 0, bytemuck::cast_slice(&flatten_rule_table(dense_rules)));
+    }
+
+    /// Uploads a full grid, replacing whatever is in the front buffer.
+    pub fn upload(&mut self, queue: &wgpu::Queue, rows: &[[Cell; WIDTH_USIZE]; HEIGHT_USIZE]) {
+        let packed: Vec<u32> = rows.iter().flatten().map(|cell| cell.0 as u32).collect();
+        queue.write_buffer(&self.cells[self.front], 0, bytemuck::cast_slice(&packed));
+    }
+
+    /// Dispatches one generation and swaps the ping-pong buffers.
+    pub fn step(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("cellulars update encoder"),
+        });
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("cellulars update pass"),
+            });
+            pass.set_pipeline(&self.pipeline);
+            pass.set_bind_group(0, &self.bind_groups[self.front], &[]);
+            let cell_count = (WIDTH_USIZE * HEIGHT_USIZE) as u32;
+            let workgroups = cell_count.div_ceil(WORKGROUP_SIZE);
+            pass.dispatch_workgroups(workgroups, 1, 1);
+        }
+        queue.submit(Some(encoder.finish()));
+        self.front = 1 - self.front;
+    }
+
+    /// Reads the current (post-step) front buffer back into a CPU grid.
+    pub fn download(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> [[Cell; WIDTH_USIZE]; HEIGHT_USIZE] {
+        let cell_count = (WIDTH_USIZE * HEIGHT_USIZE) as u64;
+        let size = cell_count * std::mem::size_of::<u32>() as u64;
+
+        let staging = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("cellulars readback buffer"),
+            size,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("cellulars readback encoder"),
+        });
+        encoder.copy_buffer_to_buffer(&self.cells[self.front], 0, &staging, 0, size);
+        queue.submit(Some(encoder.finish()));
+
+        let slice = staging.slice(..);
+        slice.map_async(wgpu::MapMode::Read, |_| {});
+        device.poll(wgpu::Maintain::Wait);
+
+        let packed: &[u32] = bytemuck::cast_slice(&slice.get_mapped_range());
+        let mut rows = [[Cell::OFF; WIDTH_USIZE]; HEIGHT_USIZE];
+        for (i, value) in packed.iter().enumerate() {
+            rows[i / WIDTH_USIZE][i % WIDTH_USIZE] = Cell(*value as u8);
+        }
+        staging.unmap();
+        rows
+    }
+}
+
+fn storage_entry(binding: u32, read_only: bool) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    }
+}
+
+/// Flattens a dense rule table into the `u32` species ids the shader expects.
+fn flatten_rule_table(dense_rules: &[Cell]) -> Vec<u32> {
+    dense_rules.iter().map(|cell| cell.0 as u32).collect()
+}
@@ -0,0 +1,357 @@
+//! egui overlay for cellulars: ruleset randomization and save/load controls.
+
+use egui::{ClippedPrimitive, Context, TexturesDelta};
+use egui_wgpu::renderer::{Renderer, ScreenDescriptor};
+use pixels::{wgpu, PixelsContext};
+use winit::event_loop::EventLoopWindowTarget;
+use winit::window::Window;
+
+/// Manages all state required for rendering egui over `Pixels`.
+pub(crate) struct Framework {
+    egui_ctx: Context,
+    egui_state: egui_winit::State,
+    screen_descriptor: ScreenDescriptor,
+    renderer: Renderer,
+    paint_jobs: Vec<ClippedPrimitive>,
+    textures: TexturesDelta,
+
+    gui: Gui,
+}
+
+/// The egui state for cellulars' controls.
+struct Gui {
+    window_open: bool,
+
+    /// Text field backing the rule-seed input.
+    rule_seed_text: String,
+    /// Set by the "Randomize" button; consumed by `main` via
+    /// `Framework::get_rule`.
+    new_rule: Option<u32>,
+    /// Fraction of cells randomized `On` by the initial grid and by a
+    /// ruleset randomization, in place of the old hardcoded `0.1`.
+    initial_density: f32,
+    /// Number of species `main` builds `World`/its rule table for; changing
+    /// this resizes `World::species` and rebuilds `rules` from scratch.
+    species_count: u8,
+
+    /// When true, `main` skips calling `World::update`.
+    paused: bool,
+    /// Set by the "Step" button; consumed by `main` via `take_step_request`.
+    step_requested: bool,
+    /// Generations per second `main` advances the simulation at, decoupled
+    /// from the window's redraw rate.
+    generations_per_second: f32,
+    /// Live generation/on%/off% readout, set by `main` each frame from
+    /// `World::get_distribution`.
+    stats: (u32, f32, f32),
+
+    /// Side length, in cells, of the square brush used for mouse painting.
+    brush_size: usize,
+
+    /// Generations a trail takes to fade from `on_color` to `off_color`.
+    fade_length: u32,
+    on_color: egui::Color32,
+    off_color: egui::Color32,
+
+    /// Text field backing the snapshot path input.
+    snapshot_path: String,
+    /// Whether to include the full `rows` grid in a saved snapshot, or
+    /// just the rule table and seed.
+    include_rows: bool,
+    save_requested: bool,
+    load_requested: bool,
+}
+
+impl Framework {
+    /// Create egui.
+    pub(crate) fn new<T>(
+        event_loop: &EventLoopWindowTarget<T>,
+        width: u32,
+        height: u32,
+        scale_factor: f32,
+        pixels: &pixels::Pixels,
+    ) -> Self {
+        let max_texture_size = pixels.device().limits().max_texture_dimension_2d as usize;
+
+        let egui_ctx = Context::default();
+        let egui_state = egui_winit::State::new(event_loop);
+        let screen_descriptor = ScreenDescriptor {
+            size_in_pixels: [width, height],
+            pixels_per_point: scale_factor,
+        };
+        let renderer = Renderer::new(pixels.device(), pixels.render_texture_format(), None, 1);
+        let textures = TexturesDelta::default();
+        let gui = Gui::new();
+
+        let _ = max_texture_size;
+
+        Self {
+            egui_ctx,
+            egui_state,
+            screen_descriptor,
+            renderer,
+            paint_jobs: Vec::new(),
+            textures,
+            gui,
+        }
+    }
+
+    /// Handle input events from the window manager.
+    pub(crate) fn handle_event(&mut self, event: &winit::event::WindowEvent) {
+        let _ = self.egui_state.on_event(&self.egui_ctx, event);
+    }
+
+    /// Resize egui.
+    pub(crate) fn resize(&mut self, width: u32, height: u32) {
+        if width > 0 && height > 0 {
+            self.screen_descriptor.size_in_pixels = [width, height];
+        }
+    }
+
+    /// Update scaling factor.
+    pub(crate) fn scale_factor(&mut self, scale_factor: f64) {
+        self.screen_descriptor.pixels_per_point = scale_factor as f32;
+    }
+
+    /// Prepare egui for rendering this frame.
+    pub(crate) fn prepare(&mut self, window: &Window) {
+        let raw_input = self.egui_state.take_egui_input(window);
+        let output = self.egui_ctx.run(raw_input, |egui_ctx| {
+            self.gui.ui(egui_ctx);
+        });
+
+        self.textures.append(output.textures_delta);
+        self.egui_state
+            .handle_platform_output(window, &self.egui_ctx, output.platform_output);
+        self.paint_jobs = self.egui_ctx.tessellate(output.shapes);
+    }
+
+    /// Render egui over the pixels frame.
+    pub(crate) fn render(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        render_target: &wgpu::TextureView,
+        context: &PixelsContext,
+    ) {
+        for (id, image_delta) in &self.textures.set {
+            self.renderer
+                .update_texture(&context.device, &context.queue, *id, image_delta);
+        }
+        self.renderer.update_buffers(
+            &context.device,
+            &context.queue,
+            encoder,
+            &self.paint_jobs,
+            &self.screen_descriptor,
+        );
+
+        let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("egui"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: render_target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        self.renderer.render(&mut rpass, &self.paint_jobs, &self.screen_descriptor);
+        drop(rpass);
+
+        let textures = std::mem::take(&mut self.textures);
+        for id in &textures.free {
+            self.renderer.free_texture(id);
+        }
+    }
+
+    /// Returns the pending randomize-rule request, if any, without clearing
+    /// it; `clear_new_rule` clears it.
+    pub(crate) fn get_rule(&self) -> Option<u32> {
+        self.gui.new_rule
+    }
+
+    /// Clears the pending randomize-rule request after `main` consumes it.
+    pub(crate) fn clear_new_rule(&mut self) {
+        self.gui.new_rule = None;
+    }
+
+    /// Takes the pending save request, if any, clearing it.
+    pub(crate) fn take_save_request(&mut self) -> bool {
+        std::mem::take(&mut self.gui.save_requested)
+    }
+
+    /// Takes the pending load request, if any, clearing it.
+    pub(crate) fn take_load_request(&mut self) -> bool {
+        std::mem::take(&mut self.gui.load_requested)
+    }
+
+    /// The path the save/load buttons currently point at.
+    pub(crate) fn snapshot_path(&self) -> &str {
+        &self.gui.snapshot_path
+    }
+
+    /// Whether the next save should include the full `rows` grid.
+    pub(crate) fn include_rows(&self) -> bool {
+        self.gui.include_rows
+    }
+
+    /// Side length, in cells, of the square mouse-painting brush.
+    pub(crate) fn brush_size(&self) -> usize {
+        self.gui.brush_size
+    }
+
+    /// Generations a cell's fade trail takes to reach `off_color`.
+    pub(crate) fn fade_length(&self) -> u32 {
+        self.gui.fade_length
+    }
+
+    /// Color drawn for an `On` cell, and the start of the fade trail.
+    pub(crate) fn on_color(&self) -> [u8; 4] {
+        self.gui.on_color.to_array()
+    }
+
+    /// Color a fully-faded `Off` cell settles to.
+    pub(crate) fn off_color(&self) -> [u8; 4] {
+        self.gui.off_color.to_array()
+    }
+
+    /// Fraction of cells that should start/reseed as `On`.
+    pub(crate) fn initial_density(&self) -> f32 {
+        self.gui.initial_density
+    }
+
+    /// Number of species `World` should have a rule table for.
+    pub(crate) fn species_count(&self) -> u8 {
+        self.gui.species_count
+    }
+
+    /// Whether `main` should skip calling `World::update` this frame.
+    pub(crate) fn paused(&self) -> bool {
+        self.gui.paused
+    }
+
+    /// Takes the pending single-step request, if any, clearing it.
+    pub(crate) fn take_step_request(&mut self) -> bool {
+        std::mem::take(&mut self.gui.step_requested)
+    }
+
+    /// Target simulation rate, independent of the redraw rate.
+    pub(crate) fn generations_per_second(&self) -> f32 {
+        self.gui.generations_per_second
+    }
+
+    /// Feeds the live generation/on%/off% readout shown in the panel.
+    pub(crate) fn set_stats(&mut self, generation: u32, on_pct: f32, off_pct: f32) {
+        self.gui.stats = (generation, on_pct, off_pct);
+    }
+
+    /// Whether egui wants to consume the mouse this frame, e.g. because the
+    /// cursor is over the "cellulars" control window. `main` checks this
+    /// before painting so clicking a slider doesn't also paint the grid
+    /// underneath it.
+    pub(crate) fn wants_pointer_input(&self) -> bool {
+        self.egui_ctx.wants_pointer_input()
+    }
+}
+
+impl Gui {
+    fn new() -> Self {
+        Self {
+            window_open: true,
+            rule_seed_text: String::from("0"),
+            new_rule: None,
+            initial_density: 0.1,
+            species_count: 2,
+            paused: false,
+            step_requested: false,
+            generations_per_second: 10.0,
+            stats: (0, 0.0, 0.0),
+            brush_size: 1,
+            fade_length: 20,
+            on_color: egui::Color32::from_rgb(0xff, 0xff, 0xff),
+            off_color: egui::Color32::from_rgb(0x59, 0x57, 0x52),
+            snapshot_path: String::from("cellulars.json"),
+            include_rows: true,
+            save_requested: false,
+            load_requested: false,
+        }
+    }
+
+    fn ui(&mut self, ctx: &Context) {
+        egui::Window::new("cellulars")
+            .open(&mut self.window_open)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    if ui.button(if self.paused { "Play" } else { "Pause" }).clicked() {
+                        self.paused = !self.paused;
+                    }
+                    if ui.button("Step").clicked() {
+                        self.step_requested = true;
+                    }
+                });
+                ui.add(
+                    egui::Slider::new(&mut self.generations_per_second, 0.5..=120.0)
+                        .text("Generations/sec")
+                        .logarithmic(true),
+                );
+                let (generation, on_pct, off_pct) = self.stats;
+                ui.label(format!(
+                    "Generation {generation}  |  On: {on_pct:.1}%  Off: {off_pct:.1}%"
+                ));
+
+                ui.separator();
+
+                ui.add(egui::Slider::new(&mut self.initial_density, 0.0..=1.0).text("Initial density"));
+                // Below 2 species there's no `Cell::ON`, and `World` relies
+                // on at least one active species existing (`paint`, `R`, and
+                // the initial-density randomization all write `Cell::ON`
+                // unconditionally).
+                ui.add(egui::Slider::new(&mut self.species_count, 2..=8).text("Species"));
+
+                ui.separator();
+
+                ui.horizontal(|ui| {
+                    ui.label("Rule seed:");
+                    ui.text_edit_singleline(&mut self.rule_seed_text);
+                    if ui.button("Randomize").clicked() {
+                        self.new_rule = self.rule_seed_text.parse().ok();
+                    }
+                });
+
+                ui.separator();
+
+                ui.add(egui::Slider::new(&mut self.brush_size, 1..=16).text("Brush size"));
+
+                ui.separator();
+
+                ui.add(egui::Slider::new(&mut self.fade_length, 1..=120).text("Fade length"));
+                ui.horizontal(|ui| {
+                    ui.label("On / trail color:");
+                    ui.color_edit_button_srgba(&mut self.on_color);
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Off (background) color:");
+                    ui.color_edit_button_srgba(&mut self.off_color);
+                });
+
+                ui.separator();
+
+                ui.horizontal(|ui| {
+                    ui.label("Snapshot path:");
+                    ui.text_edit_singleline(&mut self.snapshot_path);
+                });
+                ui.checkbox(&mut self.include_rows, "Include grid (else just rules + seed)");
+                ui.horizontal(|ui| {
+                    if ui.button("Save").clicked() {
+                        self.save_requested = true;
+                    }
+                    if ui.button("Load").clicked() {
+                        self.load_requested = true;
+                    }
+                });
+            });
+    }
+}
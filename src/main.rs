@@ -2,18 +2,23 @@
 #![forbid(unsafe_code)]
 
 use crate::gui::Framework;
-use cell::{CellState, InputState, RuleState, Rules};
+use cell::{Cell, RuleState, Rules};
 use error_iter::ErrorIter as _;
 use log::error;
 use pixels::{Error, Pixels, SurfaceTexture};
 use rand::prelude::*;
 use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
 use winit::dpi::LogicalSize;
 use winit::event::{Event, VirtualKeyCode};
 use winit::event_loop::{ControlFlow, EventLoop};
 use winit::window::WindowBuilder;
 use winit_input_helper::WinitInputHelper;
 
+mod gpu;
 mod gui;
 
 const WIDTH: u32 = 640;
@@ -28,51 +33,166 @@ const WIDTH_USIZE: usize = crate::WIDTH as usize;
 const HEIGHT_USIZE: usize = crate::HEIGHT as usize;
 
 mod cell {
+    use serde::{Deserialize, Serialize};
     use std::collections::HashMap;
 
-    #[derive(Copy, Clone, Eq, PartialEq, Hash)]
-    pub enum CellState {
-        On,
-        Off,
+    /// A cell's species id. `Cell(0)` is the default background/"Off"
+    /// species and `Cell(1)` is the default active/"On" species, so a
+    /// 2-species `World` reproduces the old binary behavior exactly.
+    #[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+    pub struct Cell(pub u8);
+
+    impl Cell {
+        pub const OFF: Cell = Cell(0);
+        pub const ON: Cell = Cell(1);
+    }
+
+    /// Display data for one species, indexed by species id in `World::species`.
+    #[derive(Clone, Serialize, Deserialize)]
+    pub struct CellData {
+        pub color: [u8; 4],
     }
 
-    #[derive(Copy, Clone, Eq, PartialEq, Hash)]
+    #[derive(Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
     pub struct RuleState(pub u32);
 
-    #[derive(Copy, Clone, Eq, PartialEq, Hash)]
-    pub struct InputState(pub [CellState; 9]);
+    /// Packs a 3x3 neighborhood into a mixed-radix index in
+    /// `0..species_count.pow(9)`, generalizing the old base-2 bit-packing
+    /// to base-`species_count` (`state[0]` is the lowest-order digit, same
+    /// as it was bit 0 of the old binary `RuleState`).
+    pub fn pack_state(state: [Cell; 9], species_count: u8) -> RuleState {
+        let mut index: u32 = 0;
+        for &Cell(value) in state.iter().rev() {
+            index = index * species_count as u32 + value as u32;
+        }
+        RuleState(index)
+    }
 
-    pub struct Rule {
-        input: InputState,
-        output: CellState,
+    /// Above this many entries, `Rules` switches from a dense `Vec` sized
+    /// `species_count^9` to a `HashMap` that only grows as new
+    /// neighborhoods are actually seen, so a large species count doesn't
+    /// require allocating and randomizing billions of entries up front.
+    const MAX_DENSE_ENTRIES: u64 = 1_000_000;
+
+    /// The rule table mapping a packed 3x3 neighborhood to its next state.
+    #[derive(Clone, Serialize, Deserialize)]
+    pub enum Rules {
+        Dense(Vec<Cell>),
+        Sparse(HashMap<RuleState, Cell>),
     }
-    pub type Rules = HashMap<InputState, CellState>;
-
-    impl From<u32> for Rule {
-        fn from(rule: u32) -> Self {
-            let mut cell_states = [CellState::Off; 9];
-            for i in 0..cell_states.len() {
-                if (0b1 << i) & rule != 0 {
-                    cell_states[i] = CellState::On;
+
+    impl Rules {
+        pub fn is_dense_for(species_count: u8) -> bool {
+            (species_count as u64).pow(9) <= MAX_DENSE_ENTRIES
+        }
+
+        /// Sets the output for `state`. A `Dense` index past the end of
+        /// `table` is ignored rather than panicking: it can only come from
+        /// a neighborhood containing a species id that no longer exists
+        /// (e.g. `rows` briefly out of sync with a shrunk `species_count`).
+        pub fn set(&mut self, state: RuleState, value: Cell) {
+            match self {
+                Rules::Dense(table) => {
+                    if let Some(slot) = table.get_mut(state.0 as usize) {
+                        *slot = value;
+                    }
+                }
+                Rules::Sparse(map) => {
+                    map.insert(state, value);
                 }
             }
-            let output = if (0b1 << 9) & rule != 0 {
-                CellState::On
-            } else {
-                CellState::Off
-            };
-            Rule {
-                input: InputState(cell_states),
-                output,
+        }
+
+        /// The full dense table, if this is a `Dense` rule set; `None` for
+        /// `Sparse`, which never holds every configuration at once.
+        pub fn dense_table(&self) -> Option<&[Cell]> {
+            match self {
+                Rules::Dense(table) => Some(table),
+                Rules::Sparse(_) => None,
             }
         }
     }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn pack_state_is_lowest_order_digit_first() {
+            // Horner's method above walks `state` in reverse, so `state[0]`
+            // ends up as the lowest-order base-`species_count` digit - the
+            // same position bit 0 held in the old binary `RuleState`.
+            let mut state = [Cell::OFF; 9];
+            state[0] = Cell(2);
+            assert_eq!(pack_state(state, 3).0, 2);
+
+            state = [Cell::OFF; 9];
+            state[1] = Cell(2);
+            assert_eq!(pack_state(state, 3).0, 2 * 3);
+
+            state = [Cell::OFF; 9];
+            state[8] = Cell(1);
+            assert_eq!(pack_state(state, 3).0, 3u32.pow(8));
+        }
+
+        #[test]
+        fn pack_state_matches_old_binary_packing_for_two_species() {
+            // With 2 species, `pack_state` should reduce to plain bit-packing:
+            // cell `i` On sets bit `i`.
+            let mut state = [Cell::OFF; 9];
+            state[0] = Cell::ON;
+            state[3] = Cell::ON;
+            assert_eq!(pack_state(state, 2).0, 0b1001);
+        }
+    }
 }
 /// Representation of the application state. In this example, a box will bounce around the screen.
 struct World {
-    rows: [[cell::CellState; WIDTH_USIZE]; HEIGHT_USIZE],
-    rules: cell::Rules,
+    rows: [[Cell; WIDTH_USIZE]; HEIGHT_USIZE],
+    /// Generations since each cell was last non-`Cell::OFF`, used to fade
+    /// its trail toward `off_color` in `draw`. Saturates rather than wrapping.
+    ages: [[u8; WIDTH_USIZE]; HEIGHT_USIZE],
+    /// Number of generations a trail takes to fully fade to `off_color`.
+    fade_length: u32,
+    on_color: [u8; 4],
+    off_color: [u8; 4],
+    /// Display color per species id. `species[0]` is the background
+    /// species; `draw` fades toward `off_color` instead of using it
+    /// directly, so `species[1..]` are the colors that actually show up.
+    species: Vec<cell::CellData>,
+    rules: Rules,
+    generation: u32,
+    /// The seed `rules` was last randomized from. Snapshots store it next to
+    /// the full rule table, and `load` seeds the grid regeneration with it.
+    seed: u64,
+    /// Continues filling a `Rules::Sparse` table lazily as `update_cpu`
+    /// encounters neighborhoods the initial randomization never visited.
+    rng: ChaCha8Rng,
+    /// Lazily created the first time the GPU path is enabled, so starting
+    /// up with `use_gpu: false` never touches wgpu at all.
+    gpu: Option<gpu::ComputeBackend>,
+    /// Runtime toggle so the CPU and GPU update paths can be compared.
+    use_gpu: bool,
+    /// Set whenever `rows` is written outside of `update_gpu` itself (CPU
+    /// steps, painting, reseeding, loading); tells the next `update_gpu`
+    /// call it must re-upload before stepping, because `gpu`'s ping-pong
+    /// buffers otherwise hold whatever generation they were last stepped to.
+    gpu_dirty: bool,
+}
+
+/// On-disk form of a `World`, written/read by `World::save`/`World::load`.
+///
+/// The rule table, species table, generation and seed are always stored.
+/// `rows` is optional: leaving it out gives a smaller file that keeps the
+/// same rules and regenerates a random grid from `seed` on load, while
+/// storing `rows` too lets a paused pattern be restored exactly.
+#[derive(Serialize, Deserialize)]
+struct WorldSnapshot {
+    rules: Rules,
+    species: Vec<cell::CellData>,
     generation: u32,
+    seed: u64,
+    rows: Option<Vec<Vec<Cell>>>,
 }
 
 fn main() -> Result<(), Error> {
@@ -104,20 +224,40 @@ fn main() -> Result<(), Error> {
 
         (pixels, framework)
     };
-    let mut world = World::new();
+    let mut world = World::new(framework.initial_density(), framework.species_count());
+    let mut last_step = std::time::Instant::now();
 
     event_loop.run(move |event, _, control_flow| {
+        world.fade_length = framework.fade_length();
+        world.on_color = framework.on_color();
+        world.off_color = framework.off_color();
+        // `draw` colors active cells from `species`, so species 1 follows
+        // the GUI's on color.
+        if let Some(on) = world.species.get_mut(1) {
+            on.color = world.on_color;
+        }
+
+        if framework.species_count() != world.species_count() {
+            world.set_species_count(framework.species_count());
+        }
+
         if let Some(new_rule) = framework.get_rule() {
-            world.rules = randomize_rules(new_rule as u64);
+            world.seed = new_rule as u64;
+            world.rules = randomize_rules(world.seed, world.species_count());
+            if let (Some(backend), Some(dense)) = (world.gpu.as_mut(), world.rules.dense_table()) {
+                backend.set_rules(pixels.queue(), dense);
+            }
+            let density = framework.initial_density();
             for i in 0..HEIGHT_USIZE {
                 for j in 0..WIDTH_USIZE {
-                    world.rows[i][j] = if rand::random::<f32>() < 0.1 {
-                        CellState::On
+                    world.rows[i][j] = if rand::random::<f32>() < density {
+                        Cell::ON
                     } else {
-                        CellState::Off
+                        Cell::OFF
                     };
                 }
             }
+            world.reset_ages();
 
             framework.clear_new_rule();
         }
@@ -150,9 +290,10 @@ fn main() -> Result<(), Error> {
             if input.key_pressed(VirtualKeyCode::K) {
                 for i in 0..HEIGHT_USIZE {
                     for j in 0..WIDTH_USIZE {
-                        world.rows[i][j] = CellState::Off;
+                        world.rows[i][j] = Cell::OFF;
                     }
                 }
+                world.reset_ages();
                 // world.rows[rand::random::<usize>() % HEIGHT_USIZE]
                 //     [rand::random::<usize>() % WIDTH_USIZE] = PixelState::On;
                 return;
@@ -161,20 +302,63 @@ fn main() -> Result<(), Error> {
             if input.key_pressed(VirtualKeyCode::R) {
                 for i in 0..HEIGHT_USIZE {
                     for j in 0..WIDTH_USIZE {
-                        world.rows[i][j] = if rand::random() {
-                            CellState::On
-                        } else {
-                            CellState::Off
-                        }
+                        world.rows[i][j] = if rand::random() { Cell::ON } else { Cell::OFF }
                     }
                 }
+                world.reset_ages();
                 // world.rows[rand::random::<usize>() % HEIGHT_USIZE]
                 //     [rand::random::<usize>() % WIDTH_USIZE] = PixelState::On;
                 return;
             }
 
-            // Update internal state and request a redraw
-            world.update();
+            if input.key_pressed(VirtualKeyCode::G) {
+                world.use_gpu = !world.use_gpu;
+            }
+
+            if input.key_pressed(VirtualKeyCode::S) || framework.take_save_request() {
+                if let Err(err) = world.save(framework.snapshot_path(), framework.include_rows()) {
+                    error!("world.save() failed: {err}");
+                }
+            }
+
+            if input.key_pressed(VirtualKeyCode::L) || framework.take_load_request() {
+                match World::load(framework.snapshot_path()) {
+                    Ok(loaded) => world = loaded,
+                    Err(err) => error!("World::load() failed: {err}"),
+                }
+            }
+
+            // Paint with the mouse: left button draws `Cell::ON`, right
+            // button draws `Cell::OFF`. While painting we skip
+            // `world.update()` so the edit is visible on screen before the
+            // next generation runs.
+            let painting =
+                !framework.wants_pointer_input() && (input.mouse_held(0) || input.mouse_held(1));
+            if painting {
+                if let Some((x, y)) = input.mouse() {
+                    if let Ok((col, row)) = pixels.window_pos_to_pixel((x, y)) {
+                        let state = if input.mouse_held(0) { Cell::ON } else { Cell::OFF };
+                        world.paint(col, row, framework.brush_size(), state);
+                    }
+                }
+            }
+
+            // Advance the simulation at `generations_per_second`, decoupled
+            // from how often the window actually redraws, unless paused;
+            // the step button forces exactly one generation regardless.
+            let step_interval =
+                std::time::Duration::from_secs_f32(1.0 / framework.generations_per_second().max(0.001));
+            let due = last_step.elapsed() >= step_interval;
+            let step_requested = framework.take_step_request();
+
+            if !painting && (step_requested || (!framework.paused() && due)) {
+                world.update(&pixels);
+                last_step = std::time::Instant::now();
+            }
+
+            let (on_pct, off_pct) = world.get_distribution();
+            framework.set_stats(world.generation, on_pct, off_pct);
+
             window.request_redraw();
         }
 
@@ -220,53 +404,247 @@ fn log_error<E: std::error::Error + 'static>(method_name: &str, err: E) {
     }
 }
 
-fn randomize_rules(seed: u64) -> Rules {
+fn to_io_error(err: serde_json::Error) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, err)
+}
+
+fn invalid_data(message: impl Into<String>) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message.into())
+}
+
+/// Checks that a loaded `Rules` table is actually shaped for
+/// `species_count`: a `Dense` table must have exactly `species_count^9`
+/// entries (the size `randomize_rules` always builds), and every species
+/// id the table maps a neighborhood *to* must be `< species_count` -
+/// `update_cpu`'s `pack_state` lookup would otherwise land past the end of
+/// the table, or the cell it returns would itself be out of range.
+fn validate_rules(rules: &Rules, species_count: u8) -> std::io::Result<()> {
+    match rules {
+        Rules::Dense(table) => {
+            let expected = (species_count as u64).pow(9);
+            if table.len() as u64 != expected {
+                return Err(invalid_data(format!(
+                    "snapshot rule table has {} entries, expected {expected} for {species_count} species",
+                    table.len()
+                )));
+            }
+            if table.iter().any(|cell| cell.0 >= species_count) {
+                return Err(invalid_data("snapshot rule table contains an out-of-range species id"));
+            }
+        }
+        Rules::Sparse(map) => {
+            if map.values().any(|cell| cell.0 >= species_count) {
+                return Err(invalid_data("snapshot rule table contains an out-of-range species id"));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Picks a random output species the same way the old binary rule table
+/// did: 90% `Cell::OFF`, with the remaining 10% split uniformly across the
+/// other species, so a 2-species table reproduces the old 10% On exactly.
+fn random_species(rng: &mut ChaCha8Rng, species_count: u8) -> Cell {
+    if species_count <= 1 || rng.gen::<f32>() >= 0.1 {
+        Cell::OFF
+    } else if species_count == 2 {
+        Cell::ON
+    } else {
+        Cell(1 + rng.gen_range(0..(species_count - 1)))
+    }
+}
+
+fn random_species_color(rng: &mut ChaCha8Rng) -> [u8; 4] {
+    [rng.gen(), rng.gen(), rng.gen(), 0xff]
+}
+
+/// Builds a rule table for `species_count` species: a full `species_count^9`
+/// dense table when that's small enough to enumerate, otherwise an empty
+/// `Sparse` table that `World::update_cpu` fills in lazily.
+fn randomize_rules(seed: u64, species_count: u8) -> Rules {
     let mut rng = ChaCha8Rng::seed_from_u64(seed);
-    let mut rules = Rules::with_capacity(362880);
-    for i in 0..362880_u32 {
-        let rule = if rng.gen::<f32>() < 0.1 {
-            CellState::On
-        } else {
-            CellState::Off
-        };
-        rules.insert(i.into(), rule);
+    if Rules::is_dense_for(species_count) {
+        let entries = (species_count as u64).pow(9);
+        let table = (0..entries).map(|_| random_species(&mut rng, species_count)).collect();
+        Rules::Dense(table)
+    } else {
+        Rules::Sparse(HashMap::new())
     }
-    rules
 }
 
 impl World {
-    fn new() -> Self {
-        let rules = randomize_rules(0);
+    /// Creates a new `World` with `species_count` species, randomizing its
+    /// first row with `density` probability of each cell starting active
+    /// (`Cell::ON`).
+    fn new(density: f32, species_count: u8) -> Self {
+        let seed = 0;
+        let mut rng = ChaCha8Rng::seed_from_u64(seed);
+
+        let on_color = [0xff, 0xff, 0xff, 0xff];
+        let off_color = [0x59, 0x57, 0x52, 0xff];
+
+        let mut species = vec![cell::CellData { color: off_color }];
+        for i in 1..species_count.max(1) {
+            let color = if i == 1 { on_color } else { random_species_color(&mut rng) };
+            species.push(cell::CellData { color });
+        }
+
+        let rules = randomize_rules(seed, species_count);
 
         let mut default = Self {
-            rows: [[CellState::Off; WIDTH_USIZE]; HEIGHT_USIZE],
+            rows: [[Cell::OFF; WIDTH_USIZE]; HEIGHT_USIZE],
+            ages: [[u8::MAX; WIDTH_USIZE]; HEIGHT_USIZE],
+            fade_length: 20,
+            on_color,
+            off_color,
+            species,
             rules,
             generation: 0,
+            seed,
+            rng,
+            gpu: None,
+            use_gpu: false,
+            gpu_dirty: true,
         };
 
         for i in 0..WIDTH_USIZE {
-            default.rows[0][i] = if rand::random() {
-                CellState::On
+            default.rows[0][i] = if rand::random::<f32>() < density {
+                Cell::ON
             } else {
-                CellState::Off
+                Cell::OFF
             };
         }
 
         default
     }
 
+    fn species_count(&self) -> u8 {
+        self.species.len() as u8
+    }
+
+    /// Resizes `species` to `species_count`, keeping existing colors (except
+    /// species 0 and 1, which are reset to `off_color`/`on_color`) and
+    /// assigning random ones to any new species, and rebuilds `rules` (its
+    /// size depends on `species_count`). Invalidates the GPU backend since
+    /// its rule buffer is sized for the old species count.
+    ///
+    /// Shrinking can leave `rows` holding species ids that no longer exist
+    /// (`pack_state` would then index past the end of a `Rules::Dense`
+    /// table), so any cell `>= target` is reset to `Cell::OFF`.
+    fn set_species_count(&mut self, species_count: u8) {
+        let target = species_count.max(1) as usize;
+
+        while self.species.len() < target {
+            let color = random_species_color(&mut self.rng);
+            self.species.push(cell::CellData { color });
+        }
+        self.species.truncate(target);
+
+        self.species[0].color = self.off_color;
+        if self.species.len() > 1 {
+            self.species[1].color = self.on_color;
+        }
+
+        for row in self.rows.iter_mut() {
+            for cell in row.iter_mut() {
+                if cell.0 as usize >= target {
+                    *cell = Cell::OFF;
+                }
+            }
+        }
+
+        self.rules = randomize_rules(self.seed, target as u8);
+        self.gpu = None;
+        self.gpu_dirty = true;
+    }
+
+    /// Writes a compact JSON snapshot of this `World` to `path`.
+    ///
+    /// When `include_rows` is false only the rule table, species table,
+    /// generation and seed are stored, which is enough to reproduce the
+    /// same rules with a fresh random grid; when true the exact `rows`
+    /// grid is stored too.
+    fn save(&self, path: impl AsRef<Path>, include_rows: bool) -> std::io::Result<()> {
+        let snapshot = WorldSnapshot {
+            rules: self.rules.clone(),
+            species: self.species.clone(),
+            generation: self.generation,
+            seed: self.seed,
+            rows: include_rows.then(|| self.rows.iter().map(|row| row.to_vec()).collect()),
+        };
+        let json = serde_json::to_string(&snapshot).map_err(to_io_error)?;
+        fs::write(path, json)
+    }
+
+    /// Loads a `World` previously written by `save`.
+    ///
+    /// If the snapshot didn't store `rows`, every cell is re-randomized with
+    /// `random_species` from a generator seeded with the snapshot's seed.
+    fn load(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let json = fs::read_to_string(path)?;
+        let snapshot: WorldSnapshot = serde_json::from_str(&json).map_err(to_io_error)?;
+        let species_count = (snapshot.species.len() as u8).max(1);
+        validate_rules(&snapshot.rules, species_count)?;
+        let mut rng = ChaCha8Rng::seed_from_u64(snapshot.seed);
+
+        let rows = match snapshot.rows {
+            Some(rows) => {
+                if rows.len() != HEIGHT_USIZE || rows.iter().any(|row| row.len() != WIDTH_USIZE) {
+                    return Err(invalid_data(format!(
+                        "snapshot grid must be {HEIGHT_USIZE}x{WIDTH_USIZE}, got {} rows",
+                        rows.len()
+                    )));
+                }
+                if rows.iter().flatten().any(|cell| cell.0 >= species_count) {
+                    return Err(invalid_data(
+                        "snapshot grid contains an out-of-range species id",
+                    ));
+                }
+                let mut grid = [[Cell::OFF; WIDTH_USIZE]; HEIGHT_USIZE];
+                for (row, saved_row) in grid.iter_mut().zip(rows) {
+                    row.copy_from_slice(&saved_row);
+                }
+                grid
+            }
+            None => {
+                let mut grid = [[Cell::OFF; WIDTH_USIZE]; HEIGHT_USIZE];
+                for row in grid.iter_mut() {
+                    for cell in row.iter_mut() {
+                        *cell = random_species(&mut rng, species_count);
+                    }
+                }
+                grid
+            }
+        };
+
+        Ok(Self {
+            rows,
+            ages: [[u8::MAX; WIDTH_USIZE]; HEIGHT_USIZE],
+            fade_length: 20,
+            on_color: [0xff, 0xff, 0xff, 0xff],
+            off_color: [0x59, 0x57, 0x52, 0xff],
+            species: snapshot.species,
+            rules: snapshot.rules,
+            generation: snapshot.generation,
+            seed: snapshot.seed,
+            rng,
+            gpu: None,
+            use_gpu: false,
+            gpu_dirty: true,
+        })
+    }
+
+    /// Returns the percentage of cells that are active (any non-`Cell::OFF`
+    /// species) and the percentage that are `Cell::OFF`.
     fn get_distribution(&self) -> (f32, f32) {
         let mut on = 0;
         let mut off = 0;
         for i in 0..(HEIGHT_USIZE) {
             for j in 0..WIDTH_USIZE {
-                match self.rows[i][j] {
-                    CellState::On => {
-                        on += 1;
-                    }
-                    CellState::Off => {
-                        off += 1;
-                    }
+                if self.rows[i][j] == Cell::OFF {
+                    off += 1;
+                } else {
+                    on += 1;
                 }
             }
         }
@@ -277,19 +655,82 @@ impl World {
         )
     }
 
-    fn update(&mut self) {
+    /// Resets every cell's age to the saturated "never active" value and
+    /// flags `gpu`'s buffers as stale, so a bulk rewrite of `rows` (clear,
+    /// randomize, reseed) doesn't leave stale fade trails or run the next
+    /// `update_gpu` against the grid it replaced.
+    fn reset_ages(&mut self) {
+        self.ages = [[u8::MAX; WIDTH_USIZE]; HEIGHT_USIZE];
+        self.gpu_dirty = true;
+    }
+
+    /// Sets an `brush x brush` block of cells centered on `(col, row)` to
+    /// `state`, clamping at the grid edges rather than wrapping.
+    fn paint(&mut self, col: usize, row: usize, brush: usize, state: Cell) {
+        let half = brush / 2;
+        let row_start = row.saturating_sub(half);
+        let col_start = col.saturating_sub(half);
+
+        for i in row_start..(row_start + brush).min(HEIGHT_USIZE) {
+            for j in col_start..(col_start + brush).min(WIDTH_USIZE) {
+                self.rows[i][j] = state;
+            }
+        }
+        self.gpu_dirty = true;
+    }
+
+    /// Advances one generation, using the GPU compute path when `use_gpu`
+    /// is set and the rule table is dense, and falling back to the CPU
+    /// walk otherwise (the GPU path needs the full rule table uploaded as
+    /// a single buffer, so a `Sparse` table always runs on the CPU).
+    fn update(&mut self, pixels: &Pixels) {
         self.generation += 1;
-        // let (on, off) = self.get_distribution();
-        // println!(
-        //     "Generation: {}\t\tOn: {}%\tOff: {}%",
-        //     self.generation, on_string, off_string
-        // );
-        // if self.generation > 100 && !(on < 0.1 || off < 0.1) {
-        //     self.generation = 0;
-        //     self.rules = randomize_rules();
-        //     println!("Nothing interesting going on, resetting!");
-        // }
-        let mut next_state = [[CellState::Off; WIDTH_USIZE]; HEIGHT_USIZE];
+
+        if self.use_gpu && self.rules.dense_table().is_some() {
+            self.update_gpu(pixels);
+        } else {
+            self.update_cpu();
+            // The CPU just wrote `rows` directly, bypassing `gpu`'s buffers.
+            self.gpu_dirty = true;
+        }
+
+        self.update_ages();
+    }
+
+    /// Steps the GPU ping-pong buffers in place and reads the result back
+    /// into `rows` for drawing. `rows` is only re-uploaded to the GPU when
+    /// `gpu_dirty` says it's out of sync (the backend was just created, or
+    /// something wrote `rows` directly since the last step) - otherwise the
+    /// buffers already hold the previous generation and stepping them again
+    /// is all that's needed.
+    fn update_gpu(&mut self, pixels: &Pixels) {
+        let device = pixels.device();
+        let queue = pixels.queue();
+        let species_count = self.species_count();
+        let dense = self
+            .rules
+            .dense_table()
+            .expect("update() only calls update_gpu for dense rule tables");
+
+        if self.gpu.is_none() {
+            self.gpu = Some(gpu::ComputeBackend::new(device, dense, species_count));
+            // Every place that drops `gpu` also sets `gpu_dirty`, so a
+            // freshly (re)created backend always falls into the upload below.
+            debug_assert!(self.gpu_dirty);
+        }
+        let backend = self.gpu.as_mut().unwrap();
+
+        if self.gpu_dirty {
+            backend.upload(queue, &self.rows);
+            self.gpu_dirty = false;
+        }
+        backend.step(device, queue);
+        self.rows = backend.download(device, queue);
+    }
+
+    fn update_cpu(&mut self) {
+        let species_count = self.species_count();
+        let mut next_state = [[Cell::OFF; WIDTH_USIZE]; HEIGHT_USIZE];
         for i in 0..(WIDTH_USIZE * HEIGHT_USIZE) {
             let row = i / WIDTH_USIZE;
             let col = i % WIDTH_USIZE;
@@ -326,141 +767,111 @@ impl World {
                 self.rows[lower_neighbor_y_index][right_neighbor_x_index], // Lower right
             ];
 
-            let next_cell_state = match self.rules.get(&state.into()) {
-                Some(next_cell_state) => *next_cell_state,
-                None => CellState::Off,
+            let packed = cell::pack_state(state, species_count);
+            let looked_up = match &self.rules {
+                // `.get` rather than indexing: a stray species id from `rows`
+                // being briefly out of sync with `species_count` would pack
+                // to an index past the end of `table` otherwise.
+                Rules::Dense(table) => table.get(packed.0 as usize).copied(),
+                Rules::Sparse(map) => map.get(&packed).copied(),
             };
-
-            next_state[row][col] = next_cell_state;
+            // A `Sparse` table hasn't seen this neighborhood before (or a
+            // `Dense` lookup fell outside the table); pick a rule for it now
+            // and remember it for next time.
+            let next_cell = looked_up.unwrap_or_else(|| {
+                let value = random_species(&mut self.rng, species_count);
+                self.rules.set(packed, value);
+                value
+            });
+
+            next_state[row][col] = next_cell;
         }
         self.rows = next_state;
     }
 
+    /// Updates the per-cell age buffer after `rows` has advanced a
+    /// generation: a cell that's active resets to age 0, and a `Cell::OFF`
+    /// cell ages by one generation since it was last active.
+    fn update_ages(&mut self) {
+        for i in 0..HEIGHT_USIZE {
+            for j in 0..WIDTH_USIZE {
+                self.ages[i][j] = if self.rows[i][j] == Cell::OFF {
+                    self.ages[i][j].saturating_add(1)
+                } else {
+                    0
+                };
+            }
+        }
+    }
+
     fn draw(&self, frame: &mut [u8]) {
         // Iterate over the 4 bytes making up the Red-Green-Blue-Alpha (RGBA)
         // pixel colors
         for (i, rgba_pixel) in frame.chunks_exact_mut(4).enumerate() {
             let row = i / WIDTH_USIZE;
             let col = i % WIDTH_USIZE;
+            let cell = self.rows[row][col];
 
-            let rgba = match self.rows[row][col] {
-                CellState::On => [0xff, 0xff, 0xff, 0xff],
-                CellState::Off => [0x59, 0x57, 0x52, 0xff],
-                // CellState::On => [0xf3, 0x7c, 0x1f, 0xff],
-                // CellState::Off => [0x59, 0x57, 0x52, 0xff],
+            let rgba = if cell == Cell::OFF {
+                let t = self.ages[row][col] as f32 / self.fade_length.max(1) as f32;
+                lerp_color(self.on_color, self.off_color, t.min(1.0))
+            } else {
+                self.species
+                    .get(cell.0 as usize)
+                    .map(|data| data.color)
+                    .unwrap_or(self.on_color)
             };
-            // let rgba = [
-            //     rgba[0] / std::cmp::max(rgba_pixel[0], 1),
-            //     rgba[1] / std::cmp::max(rgba_pixel[1], 1),
-            //     rgba[2] / std::cmp::max(rgba_pixel[2], 1),
-            //     rgba[3] / std::cmp::max(rgba_pixel[3], 1),
-            // ];
             rgba_pixel.copy_from_slice(&rgba);
         }
     }
 }
 
-impl From<u32> for RuleState {
-    fn from(value: u32) -> Self {
-        RuleState(value)
+/// Linearly interpolates two RGBA colors; `t = 0.0` is `from`, `t = 1.0` is `to`.
+fn lerp_color(from: [u8; 4], to: [u8; 4], t: f32) -> [u8; 4] {
+    let mut out = [0u8; 4];
+    for i in 0..4 {
+        out[i] = (from[i] as f32 + (to[i] as f32 - from[i] as f32) * t).round() as u8;
     }
+    out
 }
 
-impl From<[CellState; 9]> for InputState {
-    fn from(value: [CellState; 9]) -> Self {
-        Self([
-            value[0], value[1], value[2], value[3], value[4], value[5], value[6], value[7],
-            value[8],
-        ])
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn save_then_load_round_trips_rows_and_generation() {
+        let mut world = World::new(0.3, 3);
+        world.generation = 7;
+        world.rows[0][0] = Cell::ON;
+        world.rows[4][2] = Cell(2);
+
+        let path = std::env::temp_dir().join("cellulars_round_trip_test.json");
+        world.save(&path, true).expect("save should succeed");
+        let loaded = World::load(&path).expect("load should succeed");
+        fs::remove_file(&path).ok();
+
+        assert_eq!(loaded.generation, world.generation);
+        assert_eq!(loaded.seed, world.seed);
+        assert_eq!(loaded.species_count(), world.species_count());
+        assert_eq!(loaded.rows, world.rows);
     }
-}
 
-impl From<u32> for InputState {
-    fn from(value: u32) -> Self {
-        Self([
-            if ((value >> 0) & 0x1 == 1) {
-                CellState::On
-            } else {
-                CellState::Off
-            },
-            if ((value >> 1) & 0x1 == 1) {
-                CellState::On
-            } else {
-                CellState::Off
-            },
-            if ((value >> 2) & 0x1 == 1) {
-                CellState::On
-            } else {
-                CellState::Off
-            },
-            if ((value >> 3) & 0x1 == 1) {
-                CellState::On
-            } else {
-                CellState::Off
-            },
-            if ((value >> 4) & 0x1 == 1) {
-                CellState::On
-            } else {
-                CellState::Off
-            },
-            if ((value >> 5) & 0x1 == 1) {
-                CellState::On
-            } else {
-                CellState::Off
-            },
-            if ((value >> 6) & 0x1 == 1) {
-                CellState::On
-            } else {
-                CellState::Off
-            },
-            if ((value >> 7) & 0x1 == 1) {
-                CellState::On
-            } else {
-                CellState::Off
-            },
-            if ((value >> 8) & 0x1 == 1) {
-                CellState::On
-            } else {
-                CellState::Off
-            },
-        ])
-    }
-}
-impl From<[CellState; 9]> for RuleState {
-    fn from(pixel_states: [CellState; 9]) -> Self {
-        let pixel_states_as_u32 = pixel_states
-            .iter()
-            .map(|pixel_state| pixel_state.into())
-            .collect::<Vec<u32>>();
-
-        let output_state = pixel_states_as_u32[0] << 0
-            | pixel_states_as_u32[1] << 1
-            | pixel_states_as_u32[2] << 2
-            | pixel_states_as_u32[3] << 3
-            | pixel_states_as_u32[4] << 4
-            | pixel_states_as_u32[5] << 5
-            | pixel_states_as_u32[6] << 6
-            | pixel_states_as_u32[7] << 7
-            | pixel_states_as_u32[8] << 8;
-
-        RuleState(output_state)
-    }
-}
+    #[test]
+    fn load_rejects_snapshot_with_wrong_row_count() {
+        let world = World::new(0.0, 2);
+        let path = std::env::temp_dir().join("cellulars_bad_rows_test.json");
+        world.save(&path, true).expect("save should succeed");
 
-impl From<&CellState> for u32 {
-    fn from(value: &CellState) -> Self {
-        match value {
-            CellState::On => 1,
-            CellState::Off => 0,
-        }
-    }
-}
-impl From<CellState> for bool {
-    fn from(value: CellState) -> Self {
-        match value {
-            CellState::On => true,
-            CellState::Off => false,
-        }
+        let json = fs::read_to_string(&path).unwrap();
+        let mut snapshot: WorldSnapshot = serde_json::from_str(&json).unwrap();
+        snapshot.rows.as_mut().unwrap().truncate(HEIGHT_USIZE - 1);
+        fs::write(&path, serde_json::to_string(&snapshot).unwrap()).unwrap();
+
+        let result = World::load(&path);
+        fs::remove_file(&path).ok();
+
+        assert!(result.is_err());
     }
 }
 